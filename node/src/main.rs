@@ -5,11 +5,11 @@ use config::BlsKeyPair;
 use config::Clan;
 use config::Export as _;
 use config::Import as _;
-use config::{Comm, Committee, KeyPair, Parameters};
+use config::{Comm, Committee, KeyPair, Parameters, WorkerId};
 use consensus::Consensus;
 use crypto::combine_keys;
 use env_logger::Env;
-use primary::{Certificate, Primary};
+use primary::{Certificate, Primary, Worker};
 use store::Store;
 use tokio::sync::mpsc::{channel, Receiver};
 
@@ -43,12 +43,22 @@ async fn main() -> Result<()> {
                 .args_from_usage("--committee=<FILE> 'The file containing committee information'")
                 .args_from_usage("--parameters=[FILE] 'The file containing the node parameters'")
                 .args_from_usage("--store=<PATH> 'The path where to create the data store'")
+                .args_from_usage(
+                    "--consensus-protocol=[NAME] 'The ordering rule to run (sailfish or bullshark)'",
+                )
                 .subcommand(SubCommand::with_name("primary").about("Run a single primary"))
                 .subcommand(
                     SubCommand::with_name("worker")
                         .about("Run a single worker")
                         .args_from_usage("--id=<INT> 'The worker id'"),
                 )
+                .subcommand(
+                    SubCommand::with_name("authority")
+                        .about("Run a primary and one or more workers in a single process")
+                        .args_from_usage(
+                            "--workers=[INT] 'The number of workers to spawn (default: 1)'",
+                        ),
+                )
                 .setting(AppSettings::SubcommandRequiredElseHelp),
         )
         .setting(AppSettings::SubcommandRequiredElseHelp)
@@ -98,6 +108,36 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// CLI-facing choice of which protocol `Consensus::spawn` should run, mapped below to the
+/// `consensus` crate's own `ProtocolKind`. This is caller-side plumbing only: there is no
+/// `ConsensusProtocol` trait or shared `ConsensusState` here, so adding a new variant still
+/// requires the corresponding support to already exist in the `consensus` crate.
+///
+/// TODO(chunk0-3, not done): the backlog item asked for a `ConsensusProtocol` trait plus a
+/// `ConsensusState` (DAG / `last_committed` / `gc_depth`) with the Sailfish and Tusk commit
+/// rules ported into separate implementations of that trait, and `Consensus::spawn` made
+/// generic over it. None of that exists: this enum only selects between two variants of an
+/// external `consensus::ProtocolKind` the crate already had. That crate lives outside this
+/// source tree (it's not checked in here), so the trait/state port can't be done from this
+/// repo alone. Leave this request open until `consensus` itself is in scope.
+#[derive(Clone, Copy)]
+enum ConsensusProtocolKind {
+    Sailfish,
+    Bullshark,
+}
+
+impl std::str::FromStr for ConsensusProtocolKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sailfish" => Ok(Self::Sailfish),
+            "bullshark" => Ok(Self::Bullshark),
+            other => anyhow::bail!("unknown consensus protocol '{}'", other),
+        }
+    }
+}
+
 // Runs either a worker or a primary.
 async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     let ed_key_file = matches.value_of("edkeys").unwrap();
@@ -105,6 +145,11 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     let committee_file = matches.value_of("committee").unwrap();
     let parameters_file = matches.value_of("parameters");
     let store_path = matches.value_of("store").unwrap();
+    let consensus_protocol = matches
+        .value_of("consensus-protocol")
+        .map(str::parse)
+        .transpose()?
+        .unwrap_or(ConsensusProtocolKind::Sailfish);
 
     // Read the committee and node's keypair from file.
     let ed_keypair = KeyPair::import(ed_key_file).context("Failed to load the node's keypair")?;
@@ -156,7 +201,84 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
                 sorted_keys,
                 combined_pubkey,
                 parameters.clone(),
+                store.clone(),
+                /* tx_consensus */ tx_new_certificates,
+                /* rx_consensus */ rx_feedback,
+                tx_consensus_header_msg,
+                parameters.leaders_per_round,
+            );
+            // `store` is the same on-disk store the primary uses. `Consensus` is only handed it
+            // as a `Store` handle, not backed by a real `ConsensusStore`.
+            //
+            // TODO(chunk0-1, not done): the backlog item asked for a `ConsensusStore` providing
+            // atomic persistence of the last-committed round, a per-authority watermark map, and
+            // a monotonic sequence number, reloaded on startup so restarts are idempotent. That
+            // would have to live in the `consensus` crate, which isn't part of this source tree
+            // (not checked in here) and so can't be implemented from this repo alone. No such
+            // store exists yet: a restart still re-delivers from the primary's current
+            // certificates instead of resuming a prior `last_committed` state. Leave this
+            // request open until `consensus` itself is in scope.
+            Consensus::spawn(
+                committee,
                 store,
+                match consensus_protocol {
+                    ConsensusProtocolKind::Sailfish => consensus::ProtocolKind::Sailfish,
+                    ConsensusProtocolKind::Bullshark => consensus::ProtocolKind::Bullshark,
+                },
+                parameters.gc_depth,
+                /* rx_primary */ rx_new_certificates,
+                rx_consensus_header_msg,
+                /* tx_primary */ tx_feedback,
+                tx_output,
+                parameters.leaders_per_round,
+            );
+        }
+
+        // Spawn a single worker for this authority.
+        ("worker", Some(sub_matches)) => {
+            let id = sub_matches
+                .value_of("id")
+                .unwrap()
+                .parse::<WorkerId>()
+                .context("The worker id must be a positive integer")?;
+
+            Worker::spawn(ed_keypair.name, id, committee, parameters, store);
+        }
+
+        // Spawn a primary, its consensus core, and one or more workers in this single process;
+        // useful for local runs and benchmarks where primary/worker separation doesn't need to
+        // span multiple hosts.
+        ("authority", Some(sub_matches)) => {
+            let num_workers = sub_matches
+                .value_of("workers")
+                .map(str::parse::<WorkerId>)
+                .transpose()
+                .context("The number of workers must be a positive integer")?
+                .unwrap_or(1);
+
+            for id in 0..num_workers {
+                Worker::spawn(
+                    ed_keypair.name,
+                    id,
+                    committee.clone(),
+                    parameters.clone(),
+                    store.clone(),
+                );
+            }
+
+            let (tx_new_certificates, rx_new_certificates) = channel(CHANNEL_CAPACITY);
+            let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
+            let (tx_consensus_header_msg, rx_consensus_header_msg) = channel(CHANNEL_CAPACITY);
+
+            Primary::spawn(
+                ed_keypair,
+                bls_keypair,
+                committee.clone(),
+                clan.clone(),
+                sorted_keys,
+                combined_pubkey,
+                parameters.clone(),
+                store.clone(),
                 /* tx_consensus */ tx_new_certificates,
                 /* rx_consensus */ rx_feedback,
                 tx_consensus_header_msg,
@@ -164,6 +286,11 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
             );
             Consensus::spawn(
                 committee,
+                store,
+                match consensus_protocol {
+                    ConsensusProtocolKind::Sailfish => consensus::ProtocolKind::Sailfish,
+                    ConsensusProtocolKind::Bullshark => consensus::ProtocolKind::Bullshark,
+                },
                 parameters.gc_depth,
                 /* rx_primary */ rx_new_certificates,
                 rx_consensus_header_msg,
@@ -1,14 +1,16 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
-use crate::messages::{Certificate, NoVoteCert, NoVoteMsg, Timeout, TimeoutCert, Vote};
+use crate::messages::{Certificate, Header, NoVoteCert, NoVoteMsg, Timeout, TimeoutCert, Vote};
+use crate::primary::Round;
 use blsttc::{PublicKeyShareG2, SignatureShareG1};
 use config::{Clan, Committee, Stake};
-use crypto::{aggregate_sign, PublicKey, Signature};
+use crypto::{aggregate_sign, Digest, PublicKey};
 use log::info;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 
-/// Aggregates votes for a particular header into a certificate.
+/// Aggregates votes for a particular header into a certificate. Single-shot: once quorum is
+/// reached the aggregator finalizes, and further appends are ignored instead of re-triggering.
 pub struct VotesAggregator {
     committee_weight: Stake,
     clan_weight: Stake,
@@ -17,6 +19,7 @@ pub struct VotesAggregator {
     agg_sign: SignatureShareG1,
     pk_bit_vec: Vec<u128>,
     sorted_keys: Arc<Vec<PublicKeyShareG2>>,
+    finalized: bool,
 }
 
 impl VotesAggregator {
@@ -29,6 +32,7 @@ impl VotesAggregator {
             agg_sign: SignatureShareG1::default(),
             pk_bit_vec: vec![u128::MAX; (total_nodes + 127) / 128],
             sorted_keys,
+            finalized: false,
         }
     }
 
@@ -38,6 +42,10 @@ impl VotesAggregator {
         committee: &Committee,
         clan: &Clan,
     ) -> DagResult<Option<Certificate>> {
+        if self.finalized {
+            return Ok(None);
+        }
+
         let author = vote.author;
         let author_bls = committee.get_bls_public_g2(&author);
 
@@ -67,12 +75,15 @@ impl VotesAggregator {
         if self.committee_weight >= committee.quorum_threshold()
             && self.clan_weight >= clan.validity_threshold()
         {
-            self.committee_weight = 0; // Ensures quorum is only reached once.
+            self.finalized = true;
 
             return Ok(Some(Certificate {
-                header_id: vote.id,
-                round: vote.round,
-                origin: vote.origin,
+                header: Header {
+                    id: vote.id,
+                    round: vote.round,
+                    author: vote.origin,
+                    ..Header::default()
+                },
                 votes: (self.pk_bit_vec.clone(), self.agg_sign),
             }));
         }
@@ -80,11 +91,103 @@ impl VotesAggregator {
     }
 }
 
-/// Aggregate certificates and check if we reach a quorum.
+/// Tracks, per authority, how often its certificates were among those collected for a
+/// completed round over a sliding window of recent rounds, so `CertificatesAggregator` can
+/// demote authorities that are persistently absent instead of stalling every time a crashed or
+/// censoring leader's fixed slot comes up. Scores are fed by `CertificatesAggregator` from the
+/// certificates this node itself collected when a round completed, not from committed
+/// sub-DAGs; those sets are this node's local view, so different nodes can compute different
+/// demotions.
+pub struct LeaderReputation {
+    window: usize,
+    threshold: usize,
+    committed: VecDeque<HashSet<PublicKey>>,
+    scores: HashMap<PublicKey, usize>,
+}
+
+impl LeaderReputation {
+    pub fn new(window: usize, threshold: usize) -> Self {
+        Self {
+            window,
+            threshold,
+            committed: VecDeque::with_capacity(window),
+            scores: HashMap::new(),
+        }
+    }
+
+    /// Records which authorities had a certificate among those collected for a just-completed
+    /// round.
+    pub fn record_commit(&mut self, present: HashSet<PublicKey>) {
+        for author in &present {
+            *self.scores.entry(*author).or_insert(0) += 1;
+        }
+        self.committed.push_back(present);
+
+        if self.committed.len() > self.window {
+            if let Some(oldest) = self.committed.pop_front() {
+                for author in oldest {
+                    if let Some(score) = self.scores.get_mut(&author) {
+                        *score -= 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// An authority is demoted once we have a full window of history and it was present in
+    /// fewer than `threshold` of those rounds.
+    fn is_demoted(&self, author: &PublicKey) -> bool {
+        self.committed.len() >= self.window
+            && self.scores.get(author).copied().unwrap_or(0) < self.threshold
+    }
+
+    /// Returns the committee's leader list for `round`, promoting the next reliable authority
+    /// (by sorted public key) in place of any demoted leader. Each demoted slot is backed by a
+    /// distinct fallback candidate, so a round with several demoted leaders doesn't collapse onto
+    /// the same replacement and silently shrink the number of distinct leaders required.
+    pub fn leader_list(
+        &self,
+        committee: &Committee,
+        leaders_per_round: usize,
+        round: usize,
+    ) -> Vec<PublicKey> {
+        let mut fallback: Vec<_> = committee.authorities.keys().copied().collect();
+        fallback.sort();
+
+        let leaders = committee.leader_list(leaders_per_round, round);
+        let mut chosen: HashSet<PublicKey> = leaders.iter().copied().collect();
+
+        leaders
+            .into_iter()
+            .map(|leader| {
+                if !self.is_demoted(&leader) {
+                    return leader;
+                }
+                match fallback
+                    .iter()
+                    .copied()
+                    .find(|candidate| !self.is_demoted(candidate) && !chosen.contains(candidate))
+                {
+                    Some(candidate) => {
+                        chosen.remove(&leader);
+                        chosen.insert(candidate);
+                        candidate
+                    }
+                    None => leader,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Aggregate certificates and check if we reach a quorum. Single-shot: once quorum plus all
+/// required leaders have been collected, the aggregator finalizes and frees its buffers, and
+/// further appends are ignored instead of accumulating forever.
 pub struct CertificatesAggregator {
     weight: Stake,
     certificates: Vec<Certificate>,
     used: HashSet<PublicKey>,
+    finalized: bool,
 }
 
 impl CertificatesAggregator {
@@ -93,6 +196,7 @@ impl CertificatesAggregator {
             weight: 0,
             certificates: Vec::new(),
             used: HashSet::new(),
+            finalized: false,
         }
     }
 
@@ -101,7 +205,12 @@ impl CertificatesAggregator {
         certificate: Certificate,
         committee: &Committee,
         leaders_per_round: usize,
+        reputation: &mut LeaderReputation,
     ) -> DagResult<Option<Vec<Certificate>>> {
+        if self.finalized {
+            return Ok(None);
+        }
+
         let origin = certificate.origin();
 
         // Ensure it is the first time this authority votes.
@@ -109,12 +218,12 @@ impl CertificatesAggregator {
             return Ok(None);
         }
 
-        let round = certificate.round;
+        let round = certificate.round();
 
         self.certificates.push(certificate.clone());
         self.weight += committee.stake(&origin);
 
-        let leaders = committee.leader_list(leaders_per_round, round as usize);
+        let leaders = reputation.leader_list(committee, leaders_per_round, round as usize);
         for leader in leaders.iter() {
             if !self.used.contains(leader) {
                 return Ok(None);
@@ -124,26 +233,164 @@ impl CertificatesAggregator {
         info!("Got all leader for round {}", certificate.round());
 
         if self.weight >= committee.quorum_threshold() {
-            // self.weight = 0; // Ensures quorum is only reached once.
+            reputation.record_commit(self.used.clone());
+            self.finalized = true;
+            self.used = HashSet::new();
             return Ok(Some(self.certificates.drain(..).collect()));
         }
         Ok(None)
     }
 }
 
-/// Aggregates timeouts for a particular round into an action or trigger.
+/// Round-scoped collection of `VotesAggregator`s, one per header currently awaiting a quorum of
+/// votes. Entries are evicted by `gc_round` once their round falls behind `gc_depth`, so a
+/// long-running node's aggregator memory doesn't grow with every round.
+pub struct VotesAggregators {
+    by_round: BTreeMap<Round, HashMap<Digest, VotesAggregator>>,
+}
+
+impl VotesAggregators {
+    pub fn new() -> Self {
+        Self {
+            by_round: BTreeMap::new(),
+        }
+    }
+
+    pub fn entry(
+        &mut self,
+        round: Round,
+        header_id: Digest,
+        sorted_keys: &Arc<Vec<PublicKeyShareG2>>,
+        total_nodes: usize,
+    ) -> &mut VotesAggregator {
+        self.by_round
+            .entry(round)
+            .or_insert_with(HashMap::new)
+            .entry(header_id)
+            .or_insert_with(|| VotesAggregator::new(sorted_keys.clone(), total_nodes))
+    }
+
+    /// Drops all per-round aggregator state below the watermark `round - gc_depth`; the
+    /// watermark round itself is kept.
+    pub fn gc_round(&mut self, round: Round, gc_depth: Round) {
+        let watermark = round.saturating_sub(gc_depth);
+        self.by_round = self.by_round.split_off(&watermark);
+    }
+}
+
+/// Round-scoped collection of `CertificatesAggregator`s, tying their lifetime to the same
+/// `gc_depth` watermark the garbage collector already uses for the DAG.
+pub struct CertificatesAggregators {
+    by_round: BTreeMap<Round, CertificatesAggregator>,
+}
+
+impl CertificatesAggregators {
+    pub fn new() -> Self {
+        Self {
+            by_round: BTreeMap::new(),
+        }
+    }
+
+    pub fn entry(&mut self, round: Round) -> &mut CertificatesAggregator {
+        self.by_round
+            .entry(round)
+            .or_insert_with(CertificatesAggregator::new)
+    }
+
+    /// Drops all per-round aggregator state below the watermark `round - gc_depth`; the
+    /// watermark round itself is kept.
+    pub fn gc_round(&mut self, round: Round, gc_depth: Round) {
+        let watermark = round.saturating_sub(gc_depth);
+        self.by_round = self.by_round.split_off(&watermark);
+    }
+}
+
+/// Round-scoped collection of `TimeoutAggregator`s, one per round currently awaiting a quorum of
+/// timeouts, GC'd the same way as `VotesAggregators`.
+pub struct TimeoutAggregators {
+    by_round: BTreeMap<Round, TimeoutAggregator>,
+}
+
+impl TimeoutAggregators {
+    pub fn new() -> Self {
+        Self {
+            by_round: BTreeMap::new(),
+        }
+    }
+
+    pub fn entry(
+        &mut self,
+        round: Round,
+        sorted_keys: &Arc<Vec<PublicKeyShareG2>>,
+        total_nodes: usize,
+    ) -> &mut TimeoutAggregator {
+        self.by_round
+            .entry(round)
+            .or_insert_with(|| TimeoutAggregator::new(round, sorted_keys.clone(), total_nodes))
+    }
+
+    /// Drops all per-round aggregator state below the watermark `round - gc_depth`; the
+    /// watermark round itself is kept.
+    pub fn gc_round(&mut self, round: Round, gc_depth: Round) {
+        let watermark = round.saturating_sub(gc_depth);
+        self.by_round = self.by_round.split_off(&watermark);
+    }
+}
+
+/// Round-scoped collection of `NoVoteAggregator`s, one per round currently awaiting a quorum of
+/// no-vote messages, GC'd the same way as `VotesAggregators`.
+pub struct NoVoteAggregators {
+    by_round: BTreeMap<Round, NoVoteAggregator>,
+}
+
+impl NoVoteAggregators {
+    pub fn new() -> Self {
+        Self {
+            by_round: BTreeMap::new(),
+        }
+    }
+
+    pub fn entry(
+        &mut self,
+        round: Round,
+        sorted_keys: &Arc<Vec<PublicKeyShareG2>>,
+        total_nodes: usize,
+    ) -> &mut NoVoteAggregator {
+        self.by_round
+            .entry(round)
+            .or_insert_with(|| NoVoteAggregator::new(round, sorted_keys.clone(), total_nodes))
+    }
+
+    /// Drops all per-round aggregator state below the watermark `round - gc_depth`; the
+    /// watermark round itself is kept.
+    pub fn gc_round(&mut self, round: Round, gc_depth: Round) {
+        let watermark = round.saturating_sub(gc_depth);
+        self.by_round = self.by_round.split_off(&watermark);
+    }
+}
+
+/// Aggregates timeouts for a particular round into a constant-size, bit-vector-addressed
+/// `TimeoutCert`, following the same aggregate-signature scheme as `VotesAggregator`. Single-shot:
+/// once quorum is reached the aggregator finalizes, and further appends are ignored instead of
+/// re-triggering.
 pub struct TimeoutAggregator {
     weight: Stake,
-    timeouts: Vec<(PublicKey, Signature)>,
+    signer_count: usize,
     used: HashSet<PublicKey>,
+    cert: TimeoutCert,
+    sorted_keys: Arc<Vec<PublicKeyShareG2>>,
+    finalized: bool,
 }
 
 impl TimeoutAggregator {
-    pub fn new() -> Self {
+    pub fn new(round: Round, sorted_keys: Arc<Vec<PublicKeyShareG2>>, total_nodes: usize) -> Self {
         Self {
             weight: 0,
-            timeouts: Vec::new(),
+            signer_count: 0,
             used: HashSet::new(),
+            cert: TimeoutCert::new(round, total_nodes),
+            sorted_keys,
+            finalized: false,
         }
     }
 
@@ -152,37 +399,56 @@ impl TimeoutAggregator {
         timeout: Timeout,
         committee: &Committee,
     ) -> DagResult<Option<TimeoutCert>> {
+        if self.finalized {
+            return Ok(None);
+        }
+
         let author = timeout.author;
+        let author_bls = committee.get_bls_public_g2(&author);
 
         // Ensure it is the first time this authority sends a timeout.
         ensure!(self.used.insert(author), DagError::AuthorityReuse(author));
 
-        self.timeouts.push((author, timeout.signature));
+        self.cert.add_timeout(
+            author,
+            timeout.signature,
+            &self.sorted_keys,
+            author_bls,
+            self.signer_count,
+        )?;
+        self.signer_count += 1;
         self.weight += committee.stake(&author);
+
         if self.weight >= committee.quorum_threshold() {
-            // Once quorum is reached, you might want to reset for the next round or trigger an action.
-            return Ok(Some(TimeoutCert {
-                round: timeout.round.clone(),
-                timeouts: self.timeouts.clone(),
-            })); // Return the authorities that contributed to this quorum.
+            self.finalized = true;
+            return Ok(Some(self.cert.clone()));
         }
         Ok(None)
     }
 }
 
-/// Aggregates no-vote messages for a particular round into a certification.
+/// Aggregates no-vote messages for a particular round into a constant-size, bit-vector-addressed
+/// `NoVoteCert`, following the same aggregate-signature scheme as `VotesAggregator`. Single-shot:
+/// once quorum is reached the aggregator finalizes, and further appends are ignored instead of
+/// re-triggering.
 pub struct NoVoteAggregator {
     weight: Stake,
-    no_votes: Vec<(PublicKey, Signature)>,
+    signer_count: usize,
     used: HashSet<PublicKey>,
+    cert: NoVoteCert,
+    sorted_keys: Arc<Vec<PublicKeyShareG2>>,
+    finalized: bool,
 }
 
 impl NoVoteAggregator {
-    pub fn new() -> Self {
+    pub fn new(round: Round, sorted_keys: Arc<Vec<PublicKeyShareG2>>, total_nodes: usize) -> Self {
         Self {
             weight: 0,
-            no_votes: Vec::new(),
+            signer_count: 0,
             used: HashSet::new(),
+            cert: NoVoteCert::new(round, total_nodes),
+            sorted_keys,
+            finalized: false,
         }
     }
 
@@ -191,20 +457,90 @@ impl NoVoteAggregator {
         no_vote_msg: NoVoteMsg,
         committee: &Committee,
     ) -> DagResult<Option<NoVoteCert>> {
+        if self.finalized {
+            return Ok(None);
+        }
+
         let author = no_vote_msg.author;
+        let author_bls = committee.get_bls_public_g2(&author);
 
         // Ensure it is the first time this authority sends a no-vote message.
         ensure!(self.used.insert(author), DagError::AuthorityReuse(author));
 
-        self.no_votes.push((author, no_vote_msg.signature));
+        self.cert.add_no_vote(
+            author,
+            no_vote_msg.signature,
+            &self.sorted_keys,
+            author_bls,
+            self.signer_count,
+        )?;
+        self.signer_count += 1;
         self.weight += committee.stake(&author);
+
         if self.weight >= committee.quorum_threshold() {
-            // Once quorum is reached, you might reset for the next round or use the certification as needed.
-            return Ok(Some(NoVoteCert {
-                round: no_vote_msg.round.clone(),
-                no_votes: self.no_votes.clone(),
-            })); // Return the certification that aggregates the no-votes reaching quorum.
+            self.finalized = true;
+            return Ok(Some(self.cert.clone()));
         }
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // The quorum-threshold and duplicate/unknown-signer paths in `VotesAggregator::append` and
+    // friends need `Committee`/`Clan`/BLS key fixtures that live in crates not present in this
+    // tree; this test sticks to the self-contained part of the same round, `gc_round`'s watermark
+    // math, which only needs empty per-round maps.
+    #[test]
+    fn gc_round_keeps_the_watermark_round_itself() {
+        let mut aggregators = VotesAggregators::new();
+        for round in 0..=5 {
+            aggregators.by_round.insert(round, HashMap::new());
+        }
+
+        aggregators.gc_round(5, 2);
+
+        let remaining: Vec<_> = aggregators.by_round.keys().copied().collect();
+        assert_eq!(remaining, vec![3, 4, 5]);
+    }
+
+    // `LeaderReputation::record_commit`/`is_demoted` only need `PublicKey`s, not the
+    // `Committee`/BLS fixtures `leader_list` requires, so they're testable directly; use fresh
+    // keypairs from `config::KeyPair` rather than fabricating `PublicKey` bytes.
+    #[test]
+    fn is_demoted_requires_a_full_window_below_threshold() {
+        let reliable = config::KeyPair::new().name;
+        let absent = config::KeyPair::new().name;
+
+        let mut reputation = LeaderReputation::new(/* window */ 3, /* threshold */ 2);
+
+        // Fewer rounds than the window: nobody is demoted yet, even if always absent.
+        reputation.record_commit(HashSet::from([reliable]));
+        assert!(!reputation.is_demoted(&absent));
+        assert!(!reputation.is_demoted(&reliable));
+
+        // Fill out the window: `reliable` hits the threshold, `absent` never appears.
+        reputation.record_commit(HashSet::from([reliable]));
+        reputation.record_commit(HashSet::from([reliable]));
+
+        assert!(!reputation.is_demoted(&reliable));
+        assert!(reputation.is_demoted(&absent));
+    }
+
+    #[test]
+    fn record_commit_evicts_scores_outside_the_sliding_window() {
+        let author = config::KeyPair::new().name;
+        let mut reputation = LeaderReputation::new(/* window */ 2, /* threshold */ 1);
+
+        // Present for the first round, then absent for two more: once the first round falls out
+        // of the window its contribution to the score must be evicted, not just capped.
+        reputation.record_commit(HashSet::from([author]));
+        reputation.record_commit(HashSet::new());
+        reputation.record_commit(HashSet::new());
+
+        assert!(reputation.is_demoted(&author));
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::{DagError, DagResult};
+use crate::header_waiter::WaiterMessage;
+use crate::messages::Certificate;
+use crate::primary::HeaderType;
+use blsttc::PublicKeyShareG2;
+use config::Committee;
+use crypto::{Digest, Hash};
+use futures::future::try_join_all;
+use futures::stream::futures_unordered::FuturesUnordered;
+use futures::stream::StreamExt as _;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use store::Store;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
+
+/// How long we wait for a missing digest to show up in the store before asking peers again.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Either kind of data a caller's missing digests may identify.
+pub enum FetchResult {
+    Headers(Vec<HeaderType>),
+    Certificates(Vec<Certificate>),
+}
+
+/// One caller's bulk-fetch request, kept alive until every one of its digests is in the store.
+pub struct Request {
+    pub digests: BTreeSet<Digest>,
+    pub headers: bool,
+    pub reply: oneshot::Sender<DagResult<FetchResult>>,
+}
+
+/// Fetches sets of missing headers or certificates from peers in bulk, instead of forwarding one
+/// digest at a time to the header waiter. Concurrent requests that need the same digest share a
+/// single initial peer fetch for it, even when their requested sets only partially overlap;
+/// each request is still delivered (and re-validated) independently once its own digests land.
+/// Retries are not shared: on a timeout each waiting request re-requests all of its own
+/// missing digests.
+pub struct BlockSynchronizer {
+    /// The persistent storage.
+    store: Store,
+    /// The committee, used to verify fetched certificates reach quorum before delivery.
+    committee: Committee,
+    /// The committee's BLS public keys, sorted to match the bit-vector encoding in `Certificate`.
+    sorted_keys: Arc<Vec<PublicKeyShareG2>>,
+    /// Receives bulk-fetch requests. `Synchronizer::get_parents`/`deliver_certificate` do not
+    /// send here; they forward to the header and certificate waiters.
+    rx_requests: Receiver<Request>,
+    /// Fans out missing digests to peers through the existing header-waiter/helper path.
+    tx_header_waiter: Sender<WaiterMessage>,
+    /// Digests with an outstanding peer fetch, so requests sharing a digest don't each fan out
+    /// their own `SyncParents` for it.
+    fetching: HashSet<Digest>,
+}
+
+impl BlockSynchronizer {
+    pub fn spawn(
+        store: Store,
+        committee: Committee,
+        sorted_keys: Arc<Vec<PublicKeyShareG2>>,
+        rx_requests: Receiver<Request>,
+        tx_header_waiter: Sender<WaiterMessage>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                store,
+                committee,
+                sorted_keys,
+                rx_requests,
+                tx_header_waiter,
+                fetching: HashSet::new(),
+            }
+            .run()
+            .await
+        });
+    }
+
+    /// Splits `missing` into the digests nobody else is already fetching, marking each of those
+    /// as now being fetched. Digests already in `fetching` are dropped: another in-flight waiter
+    /// will deliver them, so a second `SyncParents` for the same digest would be redundant.
+    fn digests_to_fetch(missing: &[Digest], fetching: &mut HashSet<Digest>) -> Vec<Digest> {
+        missing
+            .iter()
+            .filter(|digest| fetching.insert((*digest).clone()))
+            .cloned()
+            .collect()
+    }
+
+    /// Waits for every digest in `missing` to show up in the store, re-requesting all of them
+    /// from peers in one message every `FETCH_TIMEOUT` until they do, then returns `missing` (so `run` can clear
+    /// it from `fetching`) alongside the original `request` to deliver.
+    async fn waiter(
+        missing: Vec<Digest>,
+        store: Store,
+        request: Request,
+        tx_header_waiter: Sender<WaiterMessage>,
+    ) -> (Vec<Digest>, Request) {
+        loop {
+            let waiting: Vec<_> = missing
+                .iter()
+                .map(|digest| store.clone().notify_read(digest.to_vec()))
+                .collect();
+
+            tokio::select! {
+                result = try_join_all(waiting) => {
+                    result.expect("Storage failure: killing node.");
+                    return (missing, request);
+                }
+                _ = tokio::time::sleep(FETCH_TIMEOUT) => {
+                    tx_header_waiter
+                        .send(WaiterMessage::SyncParents(
+                            missing.clone(),
+                            HeaderType::Header(Default::default()),
+                        ))
+                        .await
+                        .expect("Failed to send sync parents request");
+                }
+            }
+        }
+    }
+
+    /// Reads every requested digest back from the store (now that `notify_read` guarantees
+    /// they're present), deserializes them, and validates them before handing them to the caller.
+    async fn collect(
+        store: &Store,
+        committee: &Committee,
+        sorted_keys: &[PublicKeyShareG2],
+        digests: BTreeSet<Digest>,
+        headers: bool,
+    ) -> DagResult<FetchResult> {
+        let mut found = Vec::new();
+        for digest in &digests {
+            let bytes = store
+                .clone()
+                .read(digest.to_vec())
+                .await?
+                .expect("notify_read guarantees the key is present");
+            found.push((digest.clone(), bytes));
+        }
+        Self::deliver(committee, sorted_keys, headers, found)
+    }
+
+    /// Deserializes the fetched bytes and checks their digest before handing them back to the
+    /// caller; fetched certificates must additionally reach quorum under `committee`/`sorted_keys`.
+    fn deliver(
+        committee: &Committee,
+        sorted_keys: &[PublicKeyShareG2],
+        headers: bool,
+        found: Vec<(Digest, Vec<u8>)>,
+    ) -> DagResult<FetchResult> {
+        if headers {
+            let headers = found
+                .into_iter()
+                .map(|(digest, bytes)| {
+                    let header_msg: HeaderType =
+                        bincode::deserialize(&bytes).map_err(DagError::from)?;
+                    let header_digest = match &header_msg {
+                        HeaderType::Header(header) => header.digest(),
+                        HeaderType::HeaderInfo(header_info) => header_info.digest(),
+                    };
+                    ensure!(header_digest == digest, DagError::InvalidHeaderDigest);
+                    Ok(header_msg)
+                })
+                .collect::<DagResult<Vec<HeaderType>>>()?;
+            Ok(FetchResult::Headers(headers))
+        } else {
+            let certificates = found
+                .into_iter()
+                .map(|(digest, bytes)| {
+                    let certificate: Certificate =
+                        bincode::deserialize(&bytes).map_err(DagError::from)?;
+                    ensure!(
+                        certificate.digest() == digest,
+                        DagError::InvalidHeaderDigest
+                    );
+                    certificate.verify(committee, sorted_keys)?;
+                    Ok(certificate)
+                })
+                .collect::<DagResult<Vec<Certificate>>>()?;
+            Ok(FetchResult::Certificates(certificates))
+        }
+    }
+
+    async fn run(&mut self) {
+        let mut waiting = FuturesUnordered::new();
+
+        loop {
+            tokio::select! {
+                Some(request) = self.rx_requests.recv() => {
+                    // Resolve whatever is already in the store; only wait on what's missing.
+                    let mut missing = Vec::new();
+                    for digest in &request.digests {
+                        if self.store.read(digest.to_vec()).await.unwrap().is_none() {
+                            missing.push(digest.clone());
+                        }
+                    }
+
+                    if missing.is_empty() {
+                        let result = Self::collect(&self.store, &self.committee, &self.sorted_keys, request.digests.clone(), request.headers).await;
+                        let _ = request.reply.send(result);
+                        continue;
+                    }
+
+                    // Only fan out a peer fetch for digests nobody else is already fetching.
+                    let to_fetch = Self::digests_to_fetch(&missing, &mut self.fetching);
+                    if !to_fetch.is_empty() {
+                        self.tx_header_waiter
+                            .send(WaiterMessage::SyncParents(
+                                to_fetch,
+                                HeaderType::Header(Default::default()),
+                            ))
+                            .await
+                            .expect("Failed to send bulk sync request");
+                    }
+
+                    waiting.push(Self::waiter(missing, self.store.clone(), request, self.tx_header_waiter.clone()));
+                }
+                Some((missing, request)) = waiting.next() => {
+                    for digest in &missing {
+                        self.fetching.remove(digest);
+                    }
+                    let outcome = Self::collect(&self.store, &self.committee, &self.sorted_keys, request.digests.clone(), request.headers).await;
+                    let _ = request.reply.send(outcome);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `digests_to_fetch` only needs `Digest` fixtures, not the `Committee`/BLS keys `collect`
+    // and `deliver` require, so it's testable directly.
+    #[test]
+    fn digests_to_fetch_drops_ones_already_being_fetched() {
+        let already_fetching = Digest([1u8; 32]);
+        let new_one = Digest([2u8; 32]);
+        let mut fetching = HashSet::from([already_fetching.clone()]);
+
+        let to_fetch = BlockSynchronizer::digests_to_fetch(
+            &[already_fetching.clone(), new_one.clone()],
+            &mut fetching,
+        );
+
+        assert_eq!(to_fetch, vec![new_one.clone()]);
+        assert_eq!(fetching, HashSet::from([already_fetching, new_one]));
+    }
+}
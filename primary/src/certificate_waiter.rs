@@ -1,15 +1,23 @@
-use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::time::Duration;
 
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
-use crate::messages::{Certificate, Header};
-use crate::primary::{HeaderMessage, HeaderType};
+use crate::header_waiter::WaiterMessage;
+use crate::messages::Certificate;
+use crate::primary::{HeaderType, Round};
+use crypto::Digest;
 use futures::future::try_join_all;
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use log::error;
 use store::Store;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+
+/// How long we wait for a certificate's parents to show up in the store before asking peers
+/// for them again.
+const PARENT_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Waits to receive all the ancestors of a certificate before looping it back to the `Core`
 /// for further processing.
@@ -18,42 +26,86 @@ pub struct CertificateWaiter {
     store: Store,
     /// Receives sync commands from the `Synchronizer`.
     rx_synchronizer: Receiver<Certificate>,
+    /// Broadcasts the GC round; a pending waiter is cancelled once its certificate's round
+    /// falls below it.
+    rx_gc_round: watch::Receiver<Round>,
     /// Loops back to the core certificates for which we got all parents.
     tx_core: Sender<Certificate>,
+    /// Requests missing parents from peers through the `HeaderWaiter`.
+    tx_header_waiter: Sender<WaiterMessage>,
+    /// Header ids with an outstanding fetch request, so duplicate certificates referencing the
+    /// same header don't spawn redundant waiters.
+    requesting: HashSet<Vec<u8>>,
 }
 
 impl CertificateWaiter {
     pub fn spawn(
         store: Store,
         rx_synchronizer: Receiver<Certificate>,
+        rx_gc_round: watch::Receiver<Round>,
         tx_core: Sender<Certificate>,
+        tx_header_waiter: Sender<WaiterMessage>,
     ) {
         tokio::spawn(async move {
             Self {
                 store,
                 rx_synchronizer,
+                rx_gc_round,
                 tx_core,
+                tx_header_waiter,
+                requesting: HashSet::new(),
             }
             .run()
             .await
         });
     }
 
-    /// Helper function. It waits for particular data to become available in the storage
-    /// and then delivers the specified header.
+    /// Helper function. It waits for particular data to become available in the storage, asking
+    /// peers to fetch it if it hasn't shown up within `PARENT_WAIT_TIMEOUT`, and then delivers
+    /// the specified certificate. Returns `Ok((key, None))` if the certificate was GC'd while
+    /// waiting; `key` is always `deliver`'s header id, so `run` can drop it from `requesting`
+    /// on either outcome instead of only when a certificate is actually delivered.
     async fn waiter(
-        mut missing: Vec<(Vec<u8>, Store)>,
+        key: Vec<u8>,
+        missing: Vec<Digest>,
+        store: Store,
         deliver: Certificate,
-    ) -> DagResult<Certificate> {
-        let waiting: Vec<_> = missing
-            .iter_mut()
-            .map(|(x, y)| y.notify_read(x.to_vec()))
-            .collect();
+        header_msg: Option<HeaderType>,
+        tx_header_waiter: Sender<WaiterMessage>,
+        mut rx_gc_round: watch::Receiver<Round>,
+    ) -> DagResult<(Vec<u8>, Option<Certificate>)> {
+        loop {
+            let waiting: Vec<_> = missing
+                .iter()
+                .map(|digest| store.clone().notify_read(digest.to_vec()))
+                .collect();
 
-        try_join_all(waiting)
-            .await
-            .map(|_| deliver)
-            .map_err(DagError::from)
+            tokio::select! {
+                result = try_join_all(waiting) => {
+                    return result.map(|_| (key, Some(deliver))).map_err(DagError::from);
+                }
+                _ = tokio::time::sleep(PARENT_WAIT_TIMEOUT) => {
+                    if *rx_gc_round.borrow() > deliver.round() {
+                        return Ok((key, None));
+                    }
+                    // We only know the missing parents' digests once the certificate's own
+                    // header is in the store; otherwise `missing` holds that header's own digest,
+                    // so re-request it with a placeholder instead of waiting passively forever.
+                    let request_msg = header_msg
+                        .clone()
+                        .unwrap_or_else(|| HeaderType::Header(Default::default()));
+                    tx_header_waiter
+                        .send(WaiterMessage::SyncParents(missing.clone(), request_msg))
+                        .await
+                        .expect("Failed to send sync parents request");
+                }
+                Ok(()) = rx_gc_round.changed() => {
+                    if *rx_gc_round.borrow() > deliver.round() {
+                        return Ok((key, None));
+                    }
+                }
+            }
+        }
     }
 
     async fn run(&mut self) {
@@ -64,40 +116,59 @@ impl CertificateWaiter {
                 Some(certificate) = self.rx_synchronizer.recv() => {
                     // Add the certificate to the waiter pool. The waiter will return it to us
                     // when all its parents are in the store.
+                    let key = certificate.header.id.to_vec();
+
+                    if *self.rx_gc_round.borrow() > certificate.round() {
+                        // Already garbage collected: no need to wait for its parents.
+                        continue;
+                    }
 
-                    let key = certificate.header_id.to_vec();
+                    if !self.requesting.insert(key.clone()) {
+                        // Already waiting on this header's parents.
+                        continue;
+                    }
 
                     if let Some(res) = self.store.read(key.clone()).await.unwrap() {
-                        let header_msg = bincode::deserialize(&res).unwrap();
+                        let header_msg: HeaderType = bincode::deserialize(&res).unwrap();
 
-                        let parents: Vec<_>;
-                        match header_msg {
-                            HeaderType::Header(header) => {
-                                parents = header.parents;
-                            }
-                            HeaderType::HeaderInfo(header_info) => {
-                                parents = header_info.parents;
-                            }
-                        }
+                        let parents = match &header_msg {
+                            HeaderType::Header(header) => header.parents.clone(),
+                            HeaderType::HeaderInfo(header_info) => header_info.parents.clone(),
+                        };
 
-                        let wait_for = parents
-                        .iter()
-                        .cloned()
-                        .map(|x| (x.to_vec(), self.store.clone()))
-                        .collect();
-
-                        let fut = Self::waiter(wait_for, certificate);
-                        waiting.push(fut);
-                    }else{
-                        let wait_for = vec![(key, self.store.clone())];
-                        let fut = Self::waiter(wait_for, certificate);
-                        waiting.push(fut);
+                        let wait_for: Vec<Digest> = parents.into_iter().collect();
+                        waiting.push(Self::waiter(
+                            key,
+                            wait_for,
+                            self.store.clone(),
+                            certificate,
+                            Some(header_msg),
+                            self.tx_header_waiter.clone(),
+                            self.rx_gc_round.clone(),
+                        ));
+                    } else {
+                        waiting.push(Self::waiter(
+                            key,
+                            vec![certificate.header.id.clone()],
+                            self.store.clone(),
+                            certificate,
+                            None,
+                            self.tx_header_waiter.clone(),
+                            self.rx_gc_round.clone(),
+                        ));
                     }
                 }
                 Some(result) = waiting.next() => match result {
-                    Ok(certificate) => {
+                    Ok((key, Some(certificate))) => {
+                        self.requesting.remove(&key);
                         self.tx_core.send(certificate).await.expect("Failed to send certificate");
                     },
+                    Ok((key, None)) => {
+                        // Certificate fell below the GC round while we were waiting on its
+                        // parents: nothing left to deliver, but still stop tracking the request
+                        // so a later certificate for the same header isn't dropped as a dup.
+                        self.requesting.remove(&key);
+                    }
                     Err(e) => {
                         error!("{}", e);
                         panic!("Storage failure: killing node.");
@@ -3,6 +3,7 @@
 mod error;
 mod aggregators;
 mod batch_maker;
+mod block_synchronizer;
 mod certificate_waiter;
 mod core;
 mod garbage_collector;
@@ -24,3 +25,4 @@ pub use crate::messages::{Certificate, Header, HeaderInfo};
 pub use crate::primary::{
     ConsensusMessage, HeaderMessage, Primary, PrimaryWorkerMessage, Round, WorkerPrimaryMessage,
 };
+pub use crate::worker::Worker;
@@ -1,8 +1,9 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
 use crate::primary::Round;
-use config::{Committee, WorkerId};
-use crypto::{Digest, Hash, PublicKey};
+use blsttc::{PublicKeyShareG2, SecretKeyShareG1, SignatureShareG1};
+use config::{Committee, Stake, WorkerId};
+use crypto::{aggregate_sign, combine_keys, sign_share, verify_share, Digest, Hash, PublicKey};
 use ed25519_dalek::Digest as _;
 use ed25519_dalek::Sha512;
 use serde::{Deserialize, Serialize};
@@ -40,10 +41,7 @@ impl Header {
             no_vote_cert,
         };
         let id = header.digest();
-        Self {
-            id,
-            ..header
-        }
+        Self { id, ..header }
     }
 }
 
@@ -86,22 +84,29 @@ impl fmt::Display for Header {
 pub struct Timeout {
     pub round: Round,
     pub author: PublicKey,
+    // The author's BLS signature over the round digest, so a quorum of these can be folded
+    // into a `TimeoutCert` without trusting the author's self-reported membership alone.
+    pub signature: SignatureShareG1,
 }
 
 impl Timeout {
-    pub async fn new(
-        round: Round,
-        author: PublicKey,
-    ) -> Self {
-        let timeout = Self {
+    /// The message every signer for `round` signs over. All timeouts for a round share this one
+    /// message (rather than each signer's own `digest()`, which also binds in their author key),
+    /// which is what lets `TimeoutCert` use the fast single-message aggregate-verify path.
+    pub fn round_digest(round: Round) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(round.to_le_bytes());
+        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+    }
+
+    pub async fn new(round: Round, author: PublicKey, bls_secret: &SecretKeyShareG1) -> Self {
+        let signature = sign_share(bls_secret, &Self::round_digest(round));
+        Self {
             round,
             author,
-        };
-        Self {
-            ..timeout
+            signature,
         }
     }
-
 }
 
 impl Hash for Timeout {
@@ -115,12 +120,7 @@ impl Hash for Timeout {
 
 impl fmt::Debug for Timeout {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(
-            f,
-            "Timeout: R{}({})",
-            self.round,
-            self.author,
-        )
+        write!(f, "Timeout: R{}({})", self.round, self.author,)
     }
 }
 
@@ -134,22 +134,26 @@ impl fmt::Display for Timeout {
 pub struct NoVoteMsg {
     pub round: Round,
     pub author: PublicKey,
+    // The author's BLS signature over the round digest, folded into a `NoVoteCert` on quorum.
+    pub signature: SignatureShareG1,
 }
 
 impl NoVoteMsg {
-    pub async fn new(
-        round: Round,
-        author: PublicKey,
-    ) -> Self {
-        let msg = Self {
+    /// The message every signer for `round` signs over; see `Timeout::round_digest`.
+    pub fn round_digest(round: Round) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(round.to_le_bytes());
+        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+    }
+
+    pub async fn new(round: Round, author: PublicKey, bls_secret: &SecretKeyShareG1) -> Self {
+        let signature = sign_share(bls_secret, &Self::round_digest(round));
+        Self {
             round,
             author,
-        };
-        Self {
-            ..msg
+            signature,
         }
     }
-
 }
 
 impl Hash for NoVoteMsg {
@@ -163,12 +167,7 @@ impl Hash for NoVoteMsg {
 
 impl fmt::Debug for NoVoteMsg {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(
-            f,
-            "NoVoteMsg: R{}({})",
-            self.round,
-            self.author,
-        )
+        write!(f, "NoVoteMsg: R{}({})", self.round, self.author,)
     }
 }
 
@@ -178,18 +177,19 @@ pub struct Vote {
     pub round: Round,
     pub origin: PublicKey,
     pub author: PublicKey,
+    // The author's BLS signature over the header id, folded into a `Certificate` on quorum.
+    pub signature: SignatureShareG1,
 }
 
 impl Vote {
-    pub async fn new(
-        header: &Header,
-        author: &PublicKey,
-    ) -> Self {
+    pub async fn new(header: &Header, author: &PublicKey, bls_secret: &SecretKeyShareG1) -> Self {
+        let signature = sign_share(bls_secret, &header.id);
         let vote = Self {
             id: header.id.clone(),
             round: header.round,
             origin: header.author,
             author: *author,
+            signature,
         };
         Self { ..vote }
     }
@@ -218,58 +218,167 @@ impl fmt::Debug for Vote {
     }
 }
 
+/// Reconstructs the signer set marked by one of `VotesAggregator`/`TimeoutAggregator`/
+/// `NoVoteAggregator`'s bit-vectors (a cleared bit marks a signer) against the committee,
+/// returning the signers' combined stake and their BLS public keys so callers can check both a
+/// quorum of stake and do a single aggregate-verify pairing.
+fn signer_set(
+    bit_vec: &[u128],
+    committee: &Committee,
+    sorted_keys: &[PublicKeyShareG2],
+) -> (Stake, Vec<PublicKeyShareG2>) {
+    let mut stake = 0;
+    let mut signers = Vec::new();
+
+    for author in committee.authorities.keys() {
+        let bls_key = committee.get_bls_public_g2(author);
+        let id = match sorted_keys.binary_search(&bls_key) {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+        let chunk = id / 128;
+        let bit = id % 128;
+        if bit_vec[chunk] & (1 << bit) == 0 {
+            stake += committee.stake(author);
+            signers.push(bls_key);
+        }
+    }
+
+    (stake, signers)
+}
+
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct TimeoutCert {
     pub round: Round,
-    // Stores a list of public keys and their corresponding signatures.
-    pub timeouts: Vec<PublicKey>,
+    // The signer set (as a bit-vector over the committee's sorted BLS keys) and the
+    // aggregated signature over the round, rather than one entry per signer.
+    pub timeouts: (Vec<u128>, SignatureShareG1),
 }
 
 impl TimeoutCert {
-    pub fn new(round: Round) -> Self {
+    pub fn new(round: Round, total_nodes: usize) -> Self {
         Self {
             round,
-            timeouts: Vec::new(),
+            timeouts: (
+                vec![u128::MAX; (total_nodes + 127) / 128],
+                SignatureShareG1::default(),
+            ),
         }
     }
 
-    // Adds a timeout to the certificate. 
-    pub fn add_timeout(&mut self, author: PublicKey) -> DagResult<()> {
-        // Ensure this public key hasn't already submitted a timeout for this round
-        if self.timeouts.iter().any(|pk| *pk == author) {
-            return Err(DagError::AuthorityReuse(author));
-        }
-
-        // Add the timeout to the list
-        self.timeouts.push(author);
+    // Adds a timeout to the certificate.
+    pub fn add_timeout(
+        &mut self,
+        author: PublicKey,
+        signature: SignatureShareG1,
+        sorted_keys: &[PublicKeyShareG2],
+        author_bls: PublicKeyShareG2,
+        signer_count: usize,
+    ) -> DagResult<()> {
+        let id = sorted_keys
+            .binary_search(&author_bls)
+            .map_err(|_| DagError::UnknownAuthority(author))?;
+        let chunk = id / 128;
+        let bit = id % 128;
+
+        // Ensure this public key hasn't already submitted a timeout for this round.
+        ensure!(
+            self.timeouts.0[chunk] & (1 << bit) != 0,
+            DagError::AuthorityReuse(author)
+        );
+
+        self.timeouts.0[chunk] &= !(1 << bit);
+        self.timeouts.1 = if signer_count == 0 {
+            signature
+        } else {
+            aggregate_sign(&self.timeouts.1, &signature)
+        };
 
         Ok(())
     }
 
-    // Verifies the timeout certificate against the committee.
+    /// Verifies that the certificate's signers reach a 2f+1 stake quorum and that the
+    /// aggregated signature is valid for the combined key of exactly that signer set.
+    pub fn verify(&self, committee: &Committee, sorted_keys: &[PublicKeyShareG2]) -> DagResult<()> {
+        let (stake, signers) = signer_set(&self.timeouts.0, committee, sorted_keys);
+        ensure!(
+            stake >= committee.quorum_threshold(),
+            DagError::CertificateRequiresQuorum
+        );
+
+        let combined = combine_keys(&signers);
+        let message = Timeout::round_digest(self.round);
+        ensure!(
+            verify_share(&combined, &message, &self.timeouts.1),
+            DagError::InvalidSignature
+        );
+        Ok(())
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct NoVoteCert {
     pub round: Round,
-    pub no_votes: Vec<(PublicKey)>,
+    // The signer set (as a bit-vector over the committee's sorted BLS keys) and the
+    // aggregated signature over the round, rather than one entry per signer.
+    pub no_votes: (Vec<u128>, SignatureShareG1),
 }
 
 impl NoVoteCert {
-    pub fn new(round: Round) -> Self {
+    pub fn new(round: Round, total_nodes: usize) -> Self {
         Self {
             round,
-            no_votes: Vec::new(),
+            no_votes: (
+                vec![u128::MAX; (total_nodes + 127) / 128],
+                SignatureShareG1::default(),
+            ),
         }
     }
 
-    pub fn add_no_vote(&mut self, author: PublicKey) -> DagResult<()> {
-        if self.no_votes.iter().any(|pk| *pk == author) {
-            return Err(DagError::AuthorityReuse(author));
-        }
+    pub fn add_no_vote(
+        &mut self,
+        author: PublicKey,
+        signature: SignatureShareG1,
+        sorted_keys: &[PublicKeyShareG2],
+        author_bls: PublicKeyShareG2,
+        signer_count: usize,
+    ) -> DagResult<()> {
+        let id = sorted_keys
+            .binary_search(&author_bls)
+            .map_err(|_| DagError::UnknownAuthority(author))?;
+        let chunk = id / 128;
+        let bit = id % 128;
+
+        ensure!(
+            self.no_votes.0[chunk] & (1 << bit) != 0,
+            DagError::AuthorityReuse(author)
+        );
+
+        self.no_votes.0[chunk] &= !(1 << bit);
+        self.no_votes.1 = if signer_count == 0 {
+            signature
+        } else {
+            aggregate_sign(&self.no_votes.1, &signature)
+        };
 
-        self.no_votes.push(author);
+        Ok(())
+    }
 
+    /// Verifies that the certificate's signers reach a 2f+1 stake quorum and that the
+    /// aggregated signature is valid for the combined key of exactly that signer set.
+    pub fn verify(&self, committee: &Committee, sorted_keys: &[PublicKeyShareG2]) -> DagResult<()> {
+        let (stake, signers) = signer_set(&self.no_votes.0, committee, sorted_keys);
+        ensure!(
+            stake >= committee.quorum_threshold(),
+            DagError::CertificateRequiresQuorum
+        );
+
+        let combined = combine_keys(&signers);
+        let message = NoVoteMsg::round_digest(self.round);
+        ensure!(
+            verify_share(&combined, &message, &self.no_votes.1),
+            DagError::InvalidSignature
+        );
         Ok(())
     }
 }
@@ -277,7 +386,9 @@ impl NoVoteCert {
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Certificate {
     pub header: Header,
-    pub votes: Vec<PublicKey>,
+    // The signer set (as a bit-vector over the committee's sorted BLS keys) and the aggregated
+    // signature over the header id, rather than one public key per voter.
+    pub votes: (Vec<u128>, SignatureShareG1),
 }
 
 impl Certificate {
@@ -302,6 +413,23 @@ impl Certificate {
     pub fn origin(&self) -> PublicKey {
         self.header.author
     }
+
+    /// Verifies that the certificate's signers reach a 2f+1 stake quorum and that the
+    /// aggregated signature is valid for the combined key of exactly that signer set.
+    pub fn verify(&self, committee: &Committee, sorted_keys: &[PublicKeyShareG2]) -> DagResult<()> {
+        let (stake, signers) = signer_set(&self.votes.0, committee, sorted_keys);
+        ensure!(
+            stake >= committee.quorum_threshold(),
+            DagError::CertificateRequiresQuorum
+        );
+
+        let combined = combine_keys(&signers);
+        ensure!(
+            verify_share(&combined, &self.header.id, &self.votes.1),
+            DagError::InvalidSignature
+        );
+        Ok(())
+    }
 }
 
 impl Hash for Certificate {
@@ -310,6 +438,11 @@ impl Hash for Certificate {
         hasher.update(&self.header.id);
         hasher.update(self.round().to_le_bytes());
         hasher.update(&self.origin());
+        // Bind the aggregate signature to the signer set it was produced over, so a certificate
+        // can't be replayed with a different (but still quorum-reaching) set of signers.
+        for chunk in &self.votes.0 {
+            hasher.update(chunk.to_le_bytes());
+        }
         Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
     }
 }
@@ -335,3 +468,19 @@ impl PartialEq for Certificate {
         ret
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `TimeoutCert`/`NoVoteCert` verify as a single aggregate signature over one shared message,
+    // so every signer for a round must sign the exact same digest. Guard against `round_digest`
+    // drifting back to something author-dependent (e.g. `Hash::digest`, which folds in `author`).
+    #[test]
+    fn round_digest_is_author_independent() {
+        assert_eq!(Timeout::round_digest(7), Timeout::round_digest(7));
+        assert_eq!(NoVoteMsg::round_digest(7), NoVoteMsg::round_digest(7));
+        assert_ne!(Timeout::round_digest(7), Timeout::round_digest(8));
+        assert_ne!(NoVoteMsg::round_digest(7), NoVoteMsg::round_digest(8));
+    }
+}
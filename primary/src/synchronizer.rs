@@ -43,42 +43,47 @@ impl Synchronizer {
         }
     }
 
-    // /// Returns `true` if we have all transactions of the payload. If we don't, we return false,
-    // /// synchronize with other nodes (through our workers), and re-schedule processing of the
-    // /// header for when we will have its complete payload.
-    // pub async fn missing_payload(&mut self, header: &Header) -> DagResult<bool> {
-    //     // We don't store the payload of our own workers.
-    //     if header.author == self.name {
-    //         return Ok(false);
-    //     }
-
-    //     let mut missing = Digest::default();
-    //         // Check whether we have the batch. If one of our worker has the batch, the primary stores the pair
-    //         // (digest, worker_id) in its own storage. It is important to verify that we received the batch
-    //         // from the correct worker id to prevent the following attack:
-    //         //      1. A Bad node sends a batch X to 2f good nodes through their worker #0.
-    //         //      2. The bad node proposes a malformed block containing the batch X and claiming it comes
-    //         //         from worker #1.
-    //         //      3. The 2f good nodes do not need to sync and thus don't notice that the header is malformed.
-    //         //         The bad node together with the 2f good nodes thus certify a block containing the batch X.
-    //         //      4. The last good node will never be able to sync as it will keep sending its sync requests
-    //         //         to workers #1 (rather than workers #0). Also, clients will never be able to retrieve batch
-    //         //         X as they will be querying worker #1.
-    //     let key = header.digest().to_vec();
-    //     if self.store.read(key).await?.is_none() {
-    //         missing = header.digest()
-    //     }
-
-    //     if missing == Digest::default() {
-    //         return Ok(false);
-    //     }
-
-    //     self.tx_header_waiter
-    //         .send(WaiterMessage::SyncPayload(missing, header.clone()))
-    //         .await
-    //         .expect("Failed to send sync batch request");
-    //     Ok(true)
-    // }
+    /// Returns `false` if we have all transactions of the payload. If we don't, we return true,
+    /// synchronize with other nodes (through our workers), and re-schedule processing of the
+    /// header for when we will have its complete payload.
+    pub async fn missing_payload(&mut self, header: &Header) -> DagResult<bool> {
+        // We don't store the payload of our own workers.
+        if header.author == self.name {
+            return Ok(false);
+        }
+
+        let mut missing = Vec::new();
+        for (digest, worker_id) in &header.payload {
+            // Check whether we have the batch. If one of our workers has the batch, the primary
+            // stores it keyed by (digest, worker_id). It is important to verify that we received
+            // the batch from the correct worker id to prevent the following attack:
+            //      1. A Bad node sends a batch X to 2f good nodes through their worker #0.
+            //      2. The bad node proposes a malformed block containing the batch X and claiming it comes
+            //         from worker #1.
+            //      3. The 2f good nodes do not need to sync and thus don't notice that the header is malformed.
+            //         The bad node together with the 2f good nodes thus certify a block containing the batch X.
+            //      4. The last good node will never be able to sync as it will keep sending its sync requests
+            //         to workers #1 (rather than workers #0). Also, clients will never be able to retrieve batch
+            //         X as they will be querying worker #1.
+            let mut key = digest.to_vec();
+            key.extend_from_slice(&worker_id.to_le_bytes());
+            if self.store.read(key).await?.is_none() {
+                missing.push(digest.clone());
+            }
+        }
+
+        if missing.is_empty() {
+            return Ok(false);
+        }
+
+        for digest in missing {
+            self.tx_header_waiter
+                .send(WaiterMessage::SyncPayload(digest, header.clone()))
+                .await
+                .expect("Failed to send sync batch request");
+        }
+        Ok(true)
+    }
 
     /// Returns the parents of a header if we have them all. If at least one parent is missing,
     /// we return an empty vector, synchronize with other nodes, and re-schedule processing
@@ -132,7 +137,7 @@ impl Synchronizer {
     /// Check whether we have all the ancestors of the certificate. If we don't, send the certificate to
     /// the `CertificateWaiter` which will trigger re-processing once we have all the missing data.
     pub async fn deliver_certificate(&mut self, certificate: &Certificate) -> DagResult<bool> {
-        let key = certificate.header_id.to_vec();
+        let key = certificate.header.id.to_vec();
 
         if let Some(head) = self.store.read(key).await.unwrap() {
             let parents: Vec<_>;